@@ -1,26 +1,127 @@
-//! Password support.
+//! Password support backed by the macOS-only `SecKeychain*` APIs.
+//!
+//! For functions that also work on iOS, see the cross-platform
+//! `passwords` module.
 
 use security_framework_sys::keychain::*;
-use security_framework_sys::base::{SecKeychainRef, errSecSuccess};
+pub use security_framework_sys::keychain::{SecProtocolType, SecAuthenticationType};
+use security_framework_sys::base::{SecKeychainRef, SecKeychainItemRef, errSecSuccess};
 use security_framework_sys::keychain_item::{SecKeychainItemDelete,
-                                            SecKeychainItemModifyAttributesAndData};
+                                            SecKeychainItemModifyAttributesAndData,
+                                            SecKeychainItemGetTypeID};
 use core_foundation_sys::base::{CFTypeRef, CFRelease};
 use core_foundation::array::CFArray;
 use core_foundation::base::TCFType;
 use keychain::SecKeychain;
 use std::ptr;
+use std::slice;
+use std::fmt;
+use std::ops::Deref;
 use std::ffi::CString;
+use std::sync::atomic::{compiler_fence, Ordering};
 use libc::c_void;
 
 use cvt;
 use base::Result;
 
+/// The password bytes returned by a successful keychain lookup.
+///
+/// This holds on to the buffer the keychain allocated for us rather than
+/// copying it into a freshly-allocated `Vec`, and frees it via
+/// `SecKeychainItemFreeContent` on drop. That avoids an extra copy of the
+/// secret and shortens the time the plaintext spends on the heap.
+pub struct SecKeychainItemPassword {
+    data: *mut c_void,
+    len: usize,
+}
+
+impl Drop for SecKeychainItemPassword {
+    fn drop(&mut self) {
+        // Scrub the plaintext before handing the buffer back to the
+        // keychain to free. `write_volatile` plus a fence keeps the
+        // optimizer from eliding the writes as dead stores.
+        unsafe {
+            let bytes = self.data as *mut u8;
+            for i in 0..self.len {
+                ptr::write_volatile(bytes.offset(i as isize), 0);
+            }
+        }
+        compiler_fence(Ordering::SeqCst);
+
+        unsafe {
+            SecKeychainItemFreeContent(ptr::null(), self.data as *const c_void);
+        }
+    }
+}
+
+impl Deref for SecKeychainItemPassword {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.data as *const u8, self.len) }
+    }
+}
+
+impl AsRef<[u8]> for SecKeychainItemPassword {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+// Print a bullet per byte rather than the secret content, so passwords
+// don't leak into logs via `{:?}`.
+impl fmt::Debug for SecKeychainItemPassword {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for _ in 0..self.len {
+            try!(write!(f, "\u{2022}"));
+        }
+        Ok(())
+    }
+}
+
+impl SecKeychainItemPassword {
+    fn new(data: *mut c_void, len: usize) -> SecKeychainItemPassword {
+        SecKeychainItemPassword { data: data, len: len }
+    }
+}
+
+declare_TCFType!{
+    /// A type representing a keychain item, e.g. a generic or internet
+    /// password, as found by one of the functions in this module.
+    SecKeychainItem, SecKeychainItemRef
+}
+impl_TCFType!(SecKeychainItem, SecKeychainItemRef, SecKeychainItemGetTypeID);
+
+impl SecKeychainItem {
+    /// Replace this item's password data in place, without a second lookup.
+    pub fn set_password(&self, password: &[u8]) -> Result<()> {
+        unsafe {
+            cvt(SecKeychainItemModifyAttributesAndData(self.as_concrete_TypeRef(),
+                                                       ptr::null(),
+                                                       password.len() as u32,
+                                                       password.as_ptr()))
+        }
+    }
+
+    /// Delete this item from the keychain it resides in.
+    pub fn delete(self) {
+        unsafe {
+            SecKeychainItemDelete(self.as_concrete_TypeRef());
+        }
+    }
+}
+
 /// Find a generic password in the default list of keychains.
 ///
 /// The underlying system supports passwords with 0 values, so this
-/// returns a vector of bytes rather than a string.
+/// returns the raw bytes rather than a string. Call `.to_owned()` on the
+/// result if you need an owned `Vec<u8>`.
+///
+/// On success, also returns the matched `SecKeychainItem` so callers can
+/// update or delete it in place without performing a second lookup.
 pub fn find_generic_password(keychains: Option<&[SecKeychain]>,
-                             service: &str, account: &str) -> Result<Vec<u8>> {
+                             service: &str, account: &str)
+                             -> Result<(SecKeychainItemPassword, SecKeychainItem)> {
 
     let keychain_or_array = match keychains {
         None => ptr::null(),
@@ -36,6 +137,7 @@ pub fn find_generic_password(keychains: Option<&[SecKeychain]>,
 
     let mut raw_len = 0;
     let mut raw = ptr::null_mut();
+    let mut item = ptr::null_mut();
 
     unsafe {
         try!(cvt(SecKeychainFindGenericPassword(keychain_or_array,
@@ -45,20 +147,10 @@ pub fn find_generic_password(keychains: Option<&[SecKeychain]>,
                                                 account_name.as_ptr(),
                                                 &mut raw_len,
                                                 &mut raw,
-                                                &mut ptr::null_mut())));
-
-        // Copy the returned password.
-        // https://doc.rust-lang.org/std/ptr/fn.copy.html
-        let len = raw_len as usize;
-        let mut password = Vec::with_capacity(len);
-        password.set_len(len);
-        ptr::copy(raw, password.as_mut_ptr(), len);
-
-        // Now free the password.
-        try!(cvt(SecKeychainItemFreeContent(ptr::null(),
-                                            raw as *const c_void)));
+                                                &mut item)));
 
-        Ok(password)
+        Ok((SecKeychainItemPassword::new(raw, raw_len as usize),
+            SecKeychainItem::wrap_under_create_rule(item)))
     }
 }
 
@@ -93,8 +185,7 @@ pub fn set_generic_password(keychain_opt: Option<&SecKeychain>,
 
         match status {
             errSecSuccess => {
-                try!(cvt(SecKeychainItemModifyAttributesAndData(
-                    item, ptr::null(), password_len, password.as_ptr())));
+                try!(SecKeychainItem::wrap_under_create_rule(item).set_password(password));
             },
             _ => {
                 try!(cvt(
@@ -143,6 +234,191 @@ pub fn delete_generic_password(keychains: Option<&[SecKeychain]>,
                                                 ptr::null_mut(),
                                                 &mut item)));
 
+        SecKeychainItem::wrap_under_create_rule(item).delete();
+        Ok(())
+    }
+}
+
+/// Find an internet password in the default list of keychains.
+///
+/// The underlying system supports passwords with 0 values, so this
+/// returns the raw bytes rather than a string. Call `.to_owned()` on the
+/// result if you need an owned `Vec<u8>`.
+pub fn find_internet_password(keychains: Option<&[SecKeychain]>,
+                              server: &str, security_domain: Option<&str>,
+                              account: &str, path: &str, port: u16,
+                              protocol: SecProtocolType,
+                              authentication_type: SecAuthenticationType)
+                              -> Result<SecKeychainItemPassword> {
+
+    let keychain_or_array = match keychains {
+        None => ptr::null(),
+        Some(refs) if refs.len() == 1 => refs[0].as_CFTypeRef(),
+        Some(refs) => CFArray::from_CFTypes(refs).as_CFTypeRef(),
+    };
+
+    let server_len = server.len() as u32;
+    let server = CString::new(server).unwrap();
+
+    let security_domain_len = security_domain.map_or(0, |s| s.len() as u32);
+    let security_domain = CString::new(security_domain.unwrap_or("")).unwrap();
+
+    let account_name_len = account.len() as u32;
+    let account_name = CString::new(account).unwrap();
+
+    let path_len = path.len() as u32;
+    let path = CString::new(path).unwrap();
+
+    let mut raw_len = 0;
+    let mut raw = ptr::null_mut();
+    let mut item = ptr::null_mut();
+
+    unsafe {
+        try!(cvt(SecKeychainFindInternetPassword(keychain_or_array,
+                                                 server_len,
+                                                 server.as_ptr(),
+                                                 security_domain_len,
+                                                 security_domain.as_ptr(),
+                                                 account_name_len,
+                                                 account_name.as_ptr(),
+                                                 path_len,
+                                                 path.as_ptr(),
+                                                 port,
+                                                 protocol,
+                                                 authentication_type,
+                                                 &mut raw_len,
+                                                 &mut raw,
+                                                 &mut item)));
+
+        // Release the item ref immediately; this function only hands back
+        // the password bytes.
+        SecKeychainItem::wrap_under_create_rule(item);
+
+        Ok(SecKeychainItemPassword::new(raw, raw_len as usize))
+    }
+}
+
+/// Set an internet password in the default keychain.
+pub fn set_internet_password(keychain_opt: Option<&SecKeychain>,
+                             server: &str, security_domain: Option<&str>,
+                             account: &str, path: &str, port: u16,
+                             protocol: SecProtocolType,
+                             authentication_type: SecAuthenticationType,
+                             password: &[u8])
+                             -> Result<()> {
+
+    let keychain_ref = match keychain_opt {
+        None => ptr::null(),
+        Some(keychain) => keychain.as_CFTypeRef(),
+    };
+
+    let server_len = server.len() as u32;
+    let server = CString::new(server).unwrap();
+
+    let security_domain_len = security_domain.map_or(0, |s| s.len() as u32);
+    let security_domain = CString::new(security_domain.unwrap_or("")).unwrap();
+
+    let account_name_len = account.len() as u32;
+    let account_name = CString::new(account).unwrap();
+
+    let path_len = path.len() as u32;
+    let path = CString::new(path).unwrap();
+
+    let password_len = password.len() as u32;
+    let mut item = ptr::null_mut();
+
+    unsafe {
+        let status = SecKeychainFindInternetPassword(keychain_ref,
+                                                      server_len,
+                                                      server.as_ptr(),
+                                                      security_domain_len,
+                                                      security_domain.as_ptr(),
+                                                      account_name_len,
+                                                      account_name.as_ptr(),
+                                                      path_len,
+                                                      path.as_ptr(),
+                                                      port,
+                                                      protocol,
+                                                      authentication_type,
+                                                      ptr::null_mut(),
+                                                      ptr::null_mut(),
+                                                      &mut item);
+
+        match status {
+            errSecSuccess => {
+                try!(SecKeychainItem::wrap_under_create_rule(item).set_password(password));
+            },
+            _ => {
+                try!(cvt(
+                    SecKeychainAddInternetPassword(
+                        keychain_ref as SecKeychainRef,
+                        server_len,
+                        server.as_ptr(),
+                        security_domain_len,
+                        security_domain.as_ptr(),
+                        account_name_len,
+                        account_name.as_ptr(),
+                        path_len,
+                        path.as_ptr(),
+                        port,
+                        protocol,
+                        authentication_type,
+                        password_len,
+                        password.as_ptr(),
+                        ptr::null_mut())
+                        ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Delete an internet password.
+pub fn delete_internet_password(keychains: Option<&[SecKeychain]>,
+                                server: &str, security_domain: Option<&str>,
+                                account: &str, path: &str, port: u16,
+                                protocol: SecProtocolType,
+                                authentication_type: SecAuthenticationType)
+                                -> Result<()> {
+
+    let keychain_or_array = match keychains {
+        None => ptr::null(),
+        Some(refs) if refs.len() == 1 => refs[0].as_CFTypeRef(),
+        Some(refs) => CFArray::from_CFTypes(refs).as_CFTypeRef(),
+    };
+
+    let server_len = server.len() as u32;
+    let server = CString::new(server).unwrap();
+
+    let security_domain_len = security_domain.map_or(0, |s| s.len() as u32);
+    let security_domain = CString::new(security_domain.unwrap_or("")).unwrap();
+
+    let account_name_len = account.len() as u32;
+    let account_name = CString::new(account).unwrap();
+
+    let path_len = path.len() as u32;
+    let path = CString::new(path).unwrap();
+
+    let mut item = ptr::null_mut();
+
+    unsafe {
+        try!(cvt(SecKeychainFindInternetPassword(keychain_or_array,
+                                                 server_len,
+                                                 server.as_ptr(),
+                                                 security_domain_len,
+                                                 security_domain.as_ptr(),
+                                                 account_name_len,
+                                                 account_name.as_ptr(),
+                                                 path_len,
+                                                 path.as_ptr(),
+                                                 port,
+                                                 protocol,
+                                                 authentication_type,
+                                                 ptr::null_mut(),
+                                                 ptr::null_mut(),
+                                                 &mut item)));
+
         SecKeychainItemDelete(item);
         CFRelease(item as CFTypeRef);
         Ok(())
@@ -207,9 +483,9 @@ mod test {
 
         set_generic_password(Some(&keychains[0]),
                              service, account, &password).unwrap();
-        let found = find_generic_password(Some(&keychains),
-                                          service, account).unwrap();
-        assert_eq!(found, password);
+        let (found, _item) = find_generic_password(Some(&keychains),
+                                                   service, account).unwrap();
+        assert_eq!(&*found, &password[..]);
 
         delete_generic_password(Some(&keychains), service, account).unwrap();
 
@@ -224,8 +500,8 @@ mod test {
         let password = String::from("deadbeef").into_bytes();
 
         set_generic_password(None, service, account, &password).unwrap();
-        let found = find_generic_password(None, service, account).unwrap();
-        assert_eq!(found, password);
+        let (found, _item) = find_generic_password(None, service, account).unwrap();
+        assert_eq!(&*found, &password[..]);
 
         delete_generic_password(None, service, account).unwrap();
     }
@@ -242,17 +518,17 @@ mod test {
 
         set_generic_password(Some(&keychains[0]), service, account, &pw1)
             .expect("set_generic_password");
-        let found = find_generic_password(Some(&keychains),
-                                          service, account)
+        let (found, _item) = find_generic_password(Some(&keychains),
+                                                   service, account)
             .expect("find_generic_password");
-        assert_eq!(found, pw1);
+        assert_eq!(&*found, &pw1[..]);
 
         set_generic_password(Some(&keychains[0]), service, account, &pw2)
             .expect("set_generic_password2");
-        let found = find_generic_password(Some(&keychains),
-                                               service, account)
+        let (found, _item) = find_generic_password(Some(&keychains),
+                                                   service, account)
             .expect("find_generic_password2");
-        assert_eq!(found, pw2);
+        assert_eq!(&*found, &pw2[..]);
 
         delete_generic_password(Some(&keychains), service, account)
             .expect("delete_generic_password");
@@ -269,13 +545,84 @@ mod test {
         let pw2 = String::from("password2").into_bytes();
 
         set_generic_password(None, service, account, &pw1).unwrap();
-        let found = find_generic_password(None, service, account).unwrap();
-        assert_eq!(found, pw1);
+        let (found, _item) = find_generic_password(None, service, account).unwrap();
+        assert_eq!(&*found, &pw1[..]);
 
         set_generic_password(None, service, account, &pw2).unwrap();
-        let found = find_generic_password(None, service, account).unwrap();
-        assert_eq!(found, pw2);
+        let (found, _item) = find_generic_password(None, service, account).unwrap();
+        assert_eq!(&*found, &pw2[..]);
 
         delete_generic_password(None, service, account).unwrap();
     }
+
+    #[test]
+    fn update_and_delete_via_item_temp() {
+        let (dir, keychain) = temp_keychain_setup("update_via_item");
+        let keychains = vec![keychain];
+
+        let service = "test_update_via_item_temp";
+        let account = "this_is_the_test_account";
+        let pw1 = String::from("password1").into_bytes();
+        let pw2 = String::from("password2").into_bytes();
+
+        set_generic_password(Some(&keychains[0]), service, account, &pw1)
+            .expect("set_generic_password");
+
+        let (found, item) = find_generic_password(Some(&keychains), service, account)
+            .expect("find_generic_password");
+        assert_eq!(&*found, &pw1[..]);
+
+        item.set_password(&pw2).expect("set_password");
+
+        let (found, item) = find_generic_password(Some(&keychains), service, account)
+            .expect("find_generic_password2");
+        assert_eq!(&*found, &pw2[..]);
+
+        item.delete();
+        assert!(find_generic_password(Some(&keychains), service, account).is_err());
+
+        temp_keychain_teardown(dir);
+    }
+
+    #[test]
+    fn round_trip_internet_password_temp() {
+        let (dir, keychain) = temp_keychain_setup("round_trip_internet_password");
+        let keychains = vec![keychain];
+
+        let server = "temp.example.com";
+        let account = "temp_this_is_the_test_account";
+        let path = "/";
+        let pw1 = String::from("deadbeef").into_bytes();
+        let pw2 = String::from("fadedfad").into_bytes();
+
+        set_internet_password(Some(&keychains[0]), server, None, account, path,
+                              0, SecProtocolType::HTTPS,
+                              SecAuthenticationType::Default, &pw1)
+            .unwrap();
+        let found = find_internet_password(Some(&keychains), server, None,
+                                           account, path, 0,
+                                           SecProtocolType::HTTPS,
+                                           SecAuthenticationType::Default)
+            .unwrap();
+        assert_eq!(&*found, &pw1[..]);
+
+        // Exercise the update-in-place (errSecSuccess) branch.
+        set_internet_password(Some(&keychains[0]), server, None, account, path,
+                              0, SecProtocolType::HTTPS,
+                              SecAuthenticationType::Default, &pw2)
+            .unwrap();
+        let found = find_internet_password(Some(&keychains), server, None,
+                                           account, path, 0,
+                                           SecProtocolType::HTTPS,
+                                           SecAuthenticationType::Default)
+            .unwrap();
+        assert_eq!(&*found, &pw2[..]);
+
+        delete_internet_password(Some(&keychains), server, None, account,
+                                 path, 0, SecProtocolType::HTTPS,
+                                 SecAuthenticationType::Default)
+            .unwrap();
+
+        temp_keychain_teardown(dir);
+    }
 }
\ No newline at end of file
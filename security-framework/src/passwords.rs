@@ -0,0 +1,275 @@
+//! Cross-platform password support.
+//!
+//! Unlike the `os::macos::passwords` module, these functions are built on
+//! `SecItemAdd`/`SecItemCopyMatching`/`SecItemUpdate`/`SecItemDelete`, which
+//! are available on both macOS and iOS, rather than the macOS-only
+//! `SecKeychain*` APIs.
+
+use core_foundation::base::{CFType, TCFType};
+use core_foundation::boolean::CFBoolean;
+use core_foundation::data::CFData;
+use core_foundation::dictionary::CFDictionary;
+use core_foundation::string::CFString;
+use core_foundation_sys::data::CFDataRef;
+use security_framework_sys::base::errSecDuplicateItem;
+use security_framework_sys::item::*;
+use std::ptr;
+
+use cvt;
+use base::Result;
+use secure_password::SecurePassword;
+
+fn item_pairs(service: &str, account: &str) -> Vec<(CFType, CFType)> {
+    unsafe {
+        vec![
+            (CFString::wrap_under_get_rule(kSecClass).as_CFType(),
+             CFString::wrap_under_get_rule(kSecClassGenericPassword).as_CFType()),
+            (CFString::wrap_under_get_rule(kSecAttrService).as_CFType(),
+             CFString::new(service).as_CFType()),
+            (CFString::wrap_under_get_rule(kSecAttrAccount).as_CFType(),
+             CFString::new(account).as_CFType()),
+        ]
+    }
+}
+
+/// Find a generic password, returning its raw bytes scrubbed on drop.
+pub fn find_generic_password(service: &str, account: &str) -> Result<SecurePassword> {
+    let mut pairs = item_pairs(service, account);
+
+    unsafe {
+        pairs.push((CFString::wrap_under_get_rule(kSecReturnData).as_CFType(),
+                    CFBoolean::true_value().as_CFType()));
+        let query = CFDictionary::from_CFType_pairs(&pairs);
+
+        let mut ret = ptr::null();
+        try!(cvt(SecItemCopyMatching(query.as_concrete_TypeRef(), &mut ret)));
+
+        let data = CFData::wrap_under_create_rule(ret as CFDataRef);
+        Ok(SecurePassword::new(data.bytes().to_vec()))
+    }
+}
+
+/// Set a generic password, adding it if it doesn't already exist, or
+/// updating it in place if it does.
+pub fn set_generic_password(service: &str, account: &str, password: &[u8]) -> Result<()> {
+    set_item_password(item_pairs(service, account), password)
+}
+
+/// Delete a generic password.
+pub fn delete_generic_password(service: &str, account: &str) -> Result<()> {
+    let query = CFDictionary::from_CFType_pairs(&item_pairs(service, account));
+    unsafe {
+        cvt(SecItemDelete(query.as_concrete_TypeRef()))
+    }
+}
+
+// Add a new item matching `search_pairs`, falling back to updating the
+// matching item in place if one already exists.
+fn set_item_password(search_pairs: Vec<(CFType, CFType)>, password: &[u8]) -> Result<()> {
+    unsafe {
+        let mut add_pairs = search_pairs.clone();
+        add_pairs.push((CFString::wrap_under_get_rule(kSecValueData).as_CFType(),
+                        CFData::from_buffer(password).as_CFType()));
+        let query = CFDictionary::from_CFType_pairs(&add_pairs);
+
+        match cvt(SecItemAdd(query.as_concrete_TypeRef(), ptr::null_mut())) {
+            Ok(()) => Ok(()),
+            Err(ref err) if err.code() == errSecDuplicateItem => {
+                let search = CFDictionary::from_CFType_pairs(&search_pairs);
+                let update = CFDictionary::from_CFType_pairs(&[
+                    (CFString::wrap_under_get_rule(kSecValueData).as_CFType(),
+                     CFData::from_buffer(password).as_CFType()),
+                ]);
+
+                cvt(SecItemUpdate(search.as_concrete_TypeRef(), update.as_concrete_TypeRef()))
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// The protection level at which a keychain item's data becomes readable,
+/// see `kSecAttrAccessible`.
+pub enum Accessible {
+    /// Readable as soon as the device has been unlocked once after a
+    /// restart, and stays readable until the next restart.
+    AfterFirstUnlock,
+    /// Readable only while the device is unlocked.
+    WhenUnlocked,
+    /// Like `AfterFirstUnlock`, but never migrated to a new device.
+    AfterFirstUnlockThisDeviceOnly,
+    /// Like `WhenUnlocked`, but never migrated to a new device.
+    WhenUnlockedThisDeviceOnly,
+}
+
+impl Accessible {
+    fn value(&self) -> CFType {
+        unsafe {
+            match *self {
+                Accessible::AfterFirstUnlock => {
+                    CFString::wrap_under_get_rule(kSecAttrAccessibleAfterFirstUnlock).as_CFType()
+                }
+                Accessible::WhenUnlocked => {
+                    CFString::wrap_under_get_rule(kSecAttrAccessibleWhenUnlocked).as_CFType()
+                }
+                Accessible::AfterFirstUnlockThisDeviceOnly => {
+                    CFString::wrap_under_get_rule(kSecAttrAccessibleAfterFirstUnlockThisDeviceOnly)
+                        .as_CFType()
+                }
+                Accessible::WhenUnlockedThisDeviceOnly => {
+                    CFString::wrap_under_get_rule(kSecAttrAccessibleWhenUnlockedThisDeviceOnly)
+                        .as_CFType()
+                }
+            }
+        }
+    }
+}
+
+/// A builder for the generic-password keychain item attributes that the
+/// fixed-shape `find`/`set`/`delete_generic_password` functions can't
+/// express, such as a label, a comment, an accessibility level, or iCloud
+/// Keychain syncing.
+pub struct PasswordOptions {
+    service: String,
+    account: String,
+    label: Option<String>,
+    comment: Option<String>,
+    accessible: Option<Accessible>,
+    synchronizable: Option<bool>,
+}
+
+impl PasswordOptions {
+    /// Create a new options builder for the given service and account.
+    pub fn new(service: &str, account: &str) -> PasswordOptions {
+        PasswordOptions {
+            service: service.to_owned(),
+            account: account.to_owned(),
+            label: None,
+            comment: None,
+            accessible: None,
+            synchronizable: None,
+        }
+    }
+
+    /// A human-readable label for the item (`kSecAttrLabel`).
+    pub fn label(&mut self, label: &str) -> &mut Self {
+        self.label = Some(label.to_owned());
+        self
+    }
+
+    /// A human-readable comment for the item (`kSecAttrComment`).
+    pub fn comment(&mut self, comment: &str) -> &mut Self {
+        self.comment = Some(comment.to_owned());
+        self
+    }
+
+    /// The protection level at which the item's data becomes readable
+    /// (`kSecAttrAccessible`).
+    pub fn accessible(&mut self, accessible: Accessible) -> &mut Self {
+        self.accessible = Some(accessible);
+        self
+    }
+
+    /// Whether the item should sync via iCloud Keychain
+    /// (`kSecAttrSynchronizable`).
+    pub fn synchronizable(&mut self, synchronizable: bool) -> &mut Self {
+        self.synchronizable = Some(synchronizable);
+        self
+    }
+
+    fn pairs(&self) -> Vec<(CFType, CFType)> {
+        unsafe {
+            let mut pairs = vec![
+                (CFString::wrap_under_get_rule(kSecClass).as_CFType(),
+                 CFString::wrap_under_get_rule(kSecClassGenericPassword).as_CFType()),
+                (CFString::wrap_under_get_rule(kSecAttrService).as_CFType(),
+                 CFString::new(&self.service).as_CFType()),
+                (CFString::wrap_under_get_rule(kSecAttrAccount).as_CFType(),
+                 CFString::new(&self.account).as_CFType()),
+            ];
+
+            if let Some(ref label) = self.label {
+                pairs.push((CFString::wrap_under_get_rule(kSecAttrLabel).as_CFType(),
+                            CFString::new(label).as_CFType()));
+            }
+
+            if let Some(ref comment) = self.comment {
+                pairs.push((CFString::wrap_under_get_rule(kSecAttrComment).as_CFType(),
+                            CFString::new(comment).as_CFType()));
+            }
+
+            if let Some(ref accessible) = self.accessible {
+                pairs.push((CFString::wrap_under_get_rule(kSecAttrAccessible).as_CFType(),
+                            accessible.value()));
+            }
+
+            if let Some(synchronizable) = self.synchronizable {
+                let value = if synchronizable { CFBoolean::true_value() } else { CFBoolean::false_value() };
+                pairs.push((CFString::wrap_under_get_rule(kSecAttrSynchronizable).as_CFType(),
+                            value.as_CFType()));
+            }
+
+            pairs
+        }
+    }
+}
+
+/// Set a generic password using the attributes configured on `options`,
+/// adding it if it doesn't already exist, or updating it in place if it
+/// does.
+///
+/// When the item already exists, it is looked up by all of the attributes
+/// configured on `options` and only its password data is replaced; its
+/// label, comment, accessibility and syncing settings are left unchanged.
+pub fn set_password_options(options: &PasswordOptions, password: &[u8]) -> Result<()> {
+    set_item_password(options.pairs(), password)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // `SecItemAdd`/`SecItemCopyMatching` have no keychain-scoping parameter
+    // (unlike the `SecKeychain*` APIs in `os::macos::passwords`), so there's
+    // no temp-keychain variant of these tests; they always hit the default
+    // keychain.
+
+    #[test]
+    #[cfg(feature = "default_keychain_tests")]
+    fn round_trip_password_default() {
+        let service = "test_round_trip_password_default_cross_platform";
+        let account = "this_is_the_test_account";
+        let pw1 = String::from("password1").into_bytes();
+        let pw2 = String::from("password2").into_bytes();
+
+        set_generic_password(service, account, &pw1).expect("set_generic_password");
+        let found = find_generic_password(service, account).expect("find_generic_password");
+        assert_eq!(&*found, &pw1[..]);
+
+        // Exercise the SecItemAdd -> errSecDuplicateItem -> SecItemUpdate fallback.
+        set_generic_password(service, account, &pw2).expect("set_generic_password2");
+        let found = find_generic_password(service, account).expect("find_generic_password2");
+        assert_eq!(&*found, &pw2[..]);
+
+        delete_generic_password(service, account).expect("delete_generic_password");
+    }
+
+    #[test]
+    #[cfg(feature = "default_keychain_tests")]
+    fn round_trip_password_options_default() {
+        let service = "test_round_trip_password_options_default";
+        let account = "this_is_the_test_account";
+        let password = String::from("deadbeef").into_bytes();
+
+        let mut options = PasswordOptions::new(service, account);
+        options.label("test label")
+               .comment("test comment")
+               .accessible(Accessible::WhenUnlocked);
+
+        set_password_options(&options, &password).expect("set_password_options");
+        let found = find_generic_password(service, account).expect("find_generic_password");
+        assert_eq!(&*found, &password[..]);
+
+        delete_generic_password(service, account).expect("delete_generic_password");
+    }
+}
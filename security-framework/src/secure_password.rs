@@ -0,0 +1,67 @@
+//! A password buffer that scrubs its contents on drop.
+
+use std::fmt;
+use std::ops::Deref;
+use std::ptr;
+use std::sync::atomic::{compiler_fence, Ordering};
+
+/// An owned password buffer whose backing memory is overwritten with zeros
+/// when it is dropped, rather than left for the allocator to reuse as-is.
+///
+/// Pass a `&SecurePassword` anywhere a `&[u8]` is expected, e.g.
+/// `set_generic_password`, to avoid leaving the plaintext you're about to
+/// hand to the keychain lying around in ordinary heap memory afterwards.
+pub struct SecurePassword(Vec<u8>);
+
+impl SecurePassword {
+    /// Wrap a byte buffer so that it is zeroed on drop.
+    pub fn new(data: Vec<u8>) -> SecurePassword {
+        SecurePassword(data)
+    }
+}
+
+impl Drop for SecurePassword {
+    fn drop(&mut self) {
+        // A plain loop writing zeros would be dead-store-eliminated by the
+        // optimizer since the buffer is never read again; `write_volatile`
+        // plus a fence prevents that.
+        unsafe {
+            let bytes = self.0.as_mut_ptr();
+            for i in 0..self.0.len() {
+                ptr::write_volatile(bytes.offset(i as isize), 0);
+            }
+        }
+        compiler_fence(Ordering::SeqCst);
+    }
+}
+
+impl Deref for SecurePassword {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl AsRef<[u8]> for SecurePassword {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<Vec<u8>> for SecurePassword {
+    fn from(data: Vec<u8>) -> SecurePassword {
+        SecurePassword::new(data)
+    }
+}
+
+// Print a bullet per byte rather than the secret content, so passwords
+// don't leak into logs via `{:?}`.
+impl fmt::Debug for SecurePassword {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for _ in 0..self.0.len() {
+            try!(write!(f, "\u{2022}"));
+        }
+        Ok(())
+    }
+}